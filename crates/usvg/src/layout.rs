@@ -0,0 +1,248 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The post-conversion layout tree.
+//!
+//! Only the subset needed by the `text` module is defined here; the sibling node kinds (paths,
+//! images, filters, ...) live outside this snapshot.
+
+use std::num::NonZeroU16;
+
+use fontdb::ID;
+
+use crate::text::{FeatureTagValue, FontSynthesis, FontVariantCaps, VariationValue};
+
+/// A font stretch, mirroring the CSS `font-stretch` keywords.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FontStretch {
+    UltraCondensed,
+    ExtraCondensed,
+    Condensed,
+    SemiCondensed,
+    Normal,
+    SemiExpanded,
+    Expanded,
+    ExtraExpanded,
+    UltraExpanded,
+}
+
+impl Default for FontStretch {
+    fn default() -> Self {
+        FontStretch::Normal
+    }
+}
+
+/// A font style, mirroring the CSS `font-style` keywords.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FontStyle {
+    Normal,
+    Italic,
+    Oblique,
+}
+
+impl Default for FontStyle {
+    fn default() -> Self {
+        FontStyle::Normal
+    }
+}
+
+/// A resolved font query: the family list and style axes used to pick a face, plus the
+/// OpenType-level adjustments (`features`/`variations`) and author-intent fallbacks
+/// (`synthesis`/`variant_caps`) layered on top of it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Font {
+    pub families: Vec<svgtypes::FontFamily>,
+    pub weight: u16,
+    pub stretch: FontStretch,
+    pub style: FontStyle,
+    pub features: Vec<FeatureTagValue>,
+    pub variations: Vec<VariationValue>,
+    pub synthesis: FontSynthesis,
+    pub variant_caps: FontVariantCaps,
+}
+
+/// The metrics of a resolved face, read once in [`crate::text::FontProviderExt::load_font`].
+#[derive(Clone, Copy, Debug)]
+pub struct ResolvedFont {
+    pub id: ID,
+    pub units_per_em: NonZeroU16,
+    pub ascent: i16,
+    pub descent: i16,
+    pub x_height: NonZeroU16,
+    /// The face's cap-height, read from `OS/2.sCapHeight` when present (falling back to a
+    /// heuristic otherwise). Used to scale synthesized small caps down from full-size uppercase.
+    pub cap_height: NonZeroU16,
+    pub underline_position: i16,
+    pub underline_thickness: NonZeroU16,
+    pub line_through_position: i16,
+    pub subscript_offset: i16,
+    pub superscript_offset: i16,
+}
+
+/// A run of text sharing one [`Font`].
+#[derive(Clone, Debug)]
+pub struct TextSpan {
+    pub text: String,
+    pub font: Font,
+}
+
+/// A chunk of text anchored at a single position (one `<tspan>`/`<text>` content run before
+/// wrapping is applied).
+#[derive(Clone, Debug, Default)]
+pub struct TextChunk {
+    pub spans: Vec<TextSpan>,
+}
+
+/// A flattened, renderable group of paths. The rest of the tree (paths, images, filters, ...)
+/// lives outside this snapshot; this is an empty placeholder for `Text::flattened`.
+#[derive(Clone, Debug, Default)]
+pub struct Group;
+
+/// A 2D affine transform, using the same `[sx kx ky sy tx ty]` row layout as `tiny-skia`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub sx: f32,
+    pub kx: f32,
+    pub ky: f32,
+    pub sy: f32,
+    pub tx: f32,
+    pub ty: f32,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Transform {
+            sx: 1.0,
+            kx: 0.0,
+            ky: 0.0,
+            sy: 1.0,
+            tx: 0.0,
+            ty: 0.0,
+        }
+    }
+
+    /// A horizontal shear (`skewX`): shifts `x` by `factor * y`.
+    pub fn from_skew_x(factor: f32) -> Self {
+        Transform {
+            sx: 1.0,
+            kx: factor,
+            ky: 0.0,
+            sy: 1.0,
+            tx: 0.0,
+            ty: 0.0,
+        }
+    }
+
+    pub fn from_scale(sx: f32, sy: f32) -> Self {
+        Transform {
+            sx,
+            kx: 0.0,
+            ky: 0.0,
+            sy,
+            tx: 0.0,
+            ty: 0.0,
+        }
+    }
+
+    /// Applies `self` after `other`, i.e. `self * other` in column-vector convention.
+    pub fn pre_concat(&self, other: Transform) -> Transform {
+        Transform {
+            sx: self.sx * other.sx + self.kx * other.ky,
+            kx: self.sx * other.kx + self.kx * other.sy,
+            ky: self.ky * other.sx + self.sy * other.ky,
+            sy: self.ky * other.kx + self.sy * other.sy,
+            tx: self.sx * other.tx + self.kx * other.ty + self.tx,
+            ty: self.ky * other.tx + self.sy * other.ty + self.ty,
+        }
+    }
+
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            self.sx * x + self.kx * y + self.tx,
+            self.ky * x + self.sy * y + self.ty,
+        )
+    }
+}
+
+/// An axis-aligned rectangle in user-space units.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// A bounding-box accumulator: empty until the first [`BBox::expand`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BBox {
+    rect: Option<Rect>,
+}
+
+impl BBox {
+    pub fn expand(&mut self, other: Rect) {
+        self.rect = Some(match self.rect {
+            Some(r) => {
+                let x0 = r.x.min(other.x);
+                let y0 = r.y.min(other.y);
+                let x1 = (r.x + r.width).max(other.x + other.width);
+                let y1 = (r.y + r.height).max(other.y + other.height);
+                Rect {
+                    x: x0,
+                    y: y0,
+                    width: x1 - x0,
+                    height: y1 - y0,
+                }
+            }
+            None => other,
+        });
+    }
+
+    pub fn to_rect(&self) -> Rect {
+        self.rect.unwrap_or_default()
+    }
+
+    /// Transforms the box's corners and re-encloses them. Exact for the axis-aligned
+    /// scales/shears `text` applies; not a general replacement for a real oriented bbox.
+    pub fn transform(&self, ts: Transform) -> Option<BBox> {
+        let r = self.rect?;
+        let corners = [
+            (r.x, r.y),
+            (r.x + r.width, r.y),
+            (r.x, r.y + r.height),
+            (r.x + r.width, r.y + r.height),
+        ];
+
+        let mut out = BBox::default();
+        for (x, y) in corners {
+            let (px, py) = ts.apply(x, y);
+            out.expand(Rect {
+                x: px,
+                y: py,
+                width: 0.0,
+                height: 0.0,
+            });
+        }
+        Some(out)
+    }
+}
+
+/// A text node, from its source spans down to its shaped glyphs and flattened outlines.
+#[derive(Clone, Debug, Default)]
+pub struct Text {
+    pub chunks: Vec<TextChunk>,
+    pub abs_transform: Transform,
+    pub layouted: Vec<crate::text::layout::TextFragment>,
+    pub bounding_box: Rect,
+    pub abs_bounding_box: Rect,
+    pub flattened: Box<Group>,
+    pub stroke_bounding_box: Rect,
+    pub abs_stroke_bounding_box: Rect,
+}
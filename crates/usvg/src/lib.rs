@@ -0,0 +1,16 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! usvg's post-conversion layout tree and the logic that builds it.
+
+mod layout;
+mod text;
+
+pub use layout::{
+    BBox, Font, FontStretch, FontStyle, Group, Rect, ResolvedFont, Text, TextChunk, TextSpan,
+    Transform,
+};
+pub use text::{
+    FeatureTagValue, FontProvider, FontSynthesis, FontVariantCaps, VariationValue,
+};
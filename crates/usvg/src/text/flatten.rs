@@ -0,0 +1,127 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Converts a text node's already-shaped glyphs (see [`crate::text::layout::layout_text`]) into
+//! outlines, applying synthetic bold/oblique along the way.
+
+use rustybuzz::ttf_parser;
+
+use crate::layout::{BBox, Group, Rect, Text};
+use crate::text::{small_caps_scale, FontProvider, FontProviderExt, SYNTHETIC_OBLIQUE_SKEW};
+
+/// How far, as a fraction of the font size, a synthetically emboldened outline is dilated
+/// outward on each side. Roughly what browsers use for `font-synthesis: weight`.
+const SYNTHETIC_BOLD_DILATION: f32 = 0.02;
+
+/// Traces a glyph outline in font units and reports the bounding box it covers once placed at
+/// the glyph's pen position, scaled to its font size, and (optionally) sheared for a synthetic
+/// oblique. A real outline builder would also collect the path itself; this snapshot only has a
+/// placeholder [`Group`] to put it in, so only the bbox side effect is kept.
+///
+/// `shear` multiplies font-space `y` (which increases *upward*, ascenders positive) before `add`
+/// flips it into the downward-increasing space `origin_y - y` lives in. So a positive `shear`
+/// moves a point with positive `y` (the top of the glyph) to a *larger* `sheared_x`, i.e. to the
+/// right of the baseline — a forward-leaning italic. E.g. with `shear = SYNTHETIC_OBLIQUE_SKEW`
+/// (~0.249), a point at font-space `(x=100, y=700)` (near the top) maps to `sheared_x ≈ 274`,
+/// right of the baseline point `(x=100, y=0)`'s `sheared_x = 100`. Using a *negative* shear here
+/// would instead pull the top left of the bottom, producing a backslant.
+struct OutlineBounds {
+    scale: f32,
+    shear: f32,
+    origin_x: f32,
+    origin_y: f32,
+    dilation: f32,
+    bbox: BBox,
+}
+
+impl OutlineBounds {
+    fn add(&mut self, x: f32, y: f32) {
+        let sheared_x = x + self.shear * y;
+        let px = self.origin_x + sheared_x * self.scale;
+        let py = self.origin_y - y * self.scale;
+        self.bbox.expand(Rect {
+            x: px - self.dilation,
+            y: py - self.dilation,
+            width: self.dilation * 2.0,
+            height: self.dilation * 2.0,
+        });
+    }
+}
+
+impl ttf_parser::OutlineBuilder for OutlineBounds {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.add(x, y);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.add(x, y);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        self.add(x1, y1);
+        self.add(x, y);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.add(x1, y1);
+        self.add(x2, y2);
+        self.add(x, y);
+    }
+
+    fn close(&mut self) {}
+}
+
+/// Converts `text.layouted`'s glyph runs into outlines, returning the flattened group and its
+/// stroke bounding box.
+pub(crate) fn flatten(text: &Text, font_provider: &dyn FontProvider) -> Option<(Group, BBox)> {
+    let mut stroke_bbox = BBox::default();
+
+    for fragment in &text.layouted {
+        let Some(font) = &fragment.font else {
+            continue;
+        };
+
+        for glyph in &fragment.glyphs {
+            let synthetic = font_provider.synthetic_style(font, glyph.font.id);
+            let shear = if synthetic.oblique {
+                SYNTHETIC_OBLIQUE_SKEW
+            } else {
+                0.0
+            };
+            let dilation = if synthetic.bold {
+                glyph.font_size * SYNTHETIC_BOLD_DILATION
+            } else {
+                0.0
+            };
+            // Synthesized small caps: shrink uniformly toward the baseline, same as a real
+            // `smcp`/`c2sc` substitution would produce visually, just without the face's own
+            // small-cap glyph outlines to draw instead.
+            let caps_scale = if glyph.synthetic_small_caps {
+                small_caps_scale(&glyph.font)
+            } else {
+                1.0
+            };
+
+            let bbox = font_provider.with_face_data(glyph.font.id, |data, face_index| {
+                let face = ttf_parser::Face::parse(data, face_index).ok()?;
+                let mut outline = OutlineBounds {
+                    scale: caps_scale * glyph.font_size / glyph.font.units_per_em.get() as f32,
+                    shear,
+                    origin_x: glyph.x,
+                    origin_y: glyph.y,
+                    dilation,
+                    bbox: BBox::default(),
+                };
+                face.outline_glyph(glyph.glyph_id, &mut outline)?;
+                Some(outline.bbox)
+            });
+
+            if let Some(Some(bbox)) = bbox {
+                stroke_bbox.expand(bbox.to_rect());
+            }
+        }
+    }
+
+    Some((Group::default(), stroke_bbox))
+}
@@ -1,11 +1,288 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
 use crate::{Font, FontProvider, FontStretch, FontStyle};
 use fontdb::{Database, ID};
 use rustybuzz::ttf_parser;
 use svgtypes::FontFamily;
 
+/// A face's Unicode coverage, stored as a sorted list of disjoint, inclusive codepoint ranges.
+///
+/// This is cheap to binary-search and much smaller than the glyph table it was built from, so we
+/// only have to parse a face once no matter how many fallback lookups it's queried for.
+type Coverage = Vec<(u32, u32)>;
+
+/// A cached coverage, tagged with a cheap fingerprint of the face it was computed for.
+///
+/// `fontdb::ID` is only unique within the `Database` that produced it: two independently
+/// constructed `Database`s (e.g. one per document, or one per test) each hand out IDs starting
+/// from the same first slot, so the same `ID` value can legitimately name two unrelated faces.
+/// The fingerprint lets us detect that case and recompute instead of serving a stale, wrong-face
+/// coverage.
+struct CachedCoverage {
+    fingerprint: (String, u32),
+    ranges: Coverage,
+}
+
+/// Per-face coverage, computed lazily and memoized for the lifetime of the process. This is keyed
+/// by `fontdb::ID` rather than stored on `Database` because `fontdb` gives us no hook to attach
+/// extra state to a face; entries are never evicted, but since the key space is bounded by the
+/// number of faces a process ever loads, this doesn't grow unboundedly in practice.
+fn coverage_cache() -> &'static Mutex<HashMap<ID, CachedCoverage>> {
+    static CACHE: OnceLock<Mutex<HashMap<ID, CachedCoverage>>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// A cheap, good-enough-to-detect-collisions identifier for `face`.
+fn face_fingerprint(face: &fontdb::FaceInfo) -> (String, u32) {
+    (face.post_script_name.clone(), face.index)
+}
+
+/// Builds the coverage of a single face by walking its cmap once.
+fn build_coverage(db: &Database, id: ID) -> Coverage {
+    let coverage = db.with_face_data(id, |font_data, face_index| -> Option<Coverage> {
+        let font = ttf_parser::Face::parse(font_data, face_index).ok()?;
+
+        let mut codepoints = Vec::new();
+        for subtable in font.tables().cmap?.subtables.into_iter() {
+            subtable.codepoints(|c| {
+                codepoints.push(c);
+                if let Some(unicode) = symbol_remap(c) {
+                    codepoints.push(unicode);
+                }
+            });
+        }
+
+        Some(merge_sorted_into_ranges(codepoints))
+    });
+
+    coverage.flatten().unwrap_or_default()
+}
+
+/// Symbol-encoded (3,0) cmap subtables map their glyphs at `0xF000+c` rather than at the Unicode
+/// codepoint itself; `Face::glyph_index` knows to retry there for callers that pass the "real"
+/// codepoint, so the coverage we build from a raw cmap walk has to include the remap too, or a
+/// symbol/Wingdings-style face would never be picked for fallback. E.g. `0xF041` (symbol-encoded
+/// `A`) also covers `0x41`.
+fn symbol_remap(c: u32) -> Option<u32> {
+    match c {
+        0xF000..=0xF0FF => Some(c - 0xF000),
+        _ => None,
+    }
+}
+
+/// Sorts, dedups and run-length-merges a list of codepoints into disjoint, inclusive ranges.
+fn merge_sorted_into_ranges(mut codepoints: Vec<u32>) -> Coverage {
+    codepoints.sort_unstable();
+    codepoints.dedup();
+
+    let mut ranges: Coverage = Vec::new();
+    for c in codepoints {
+        match ranges.last_mut() {
+            Some((_, end)) if c == *end + 1 => *end = c,
+            _ => ranges.push((c, c)),
+        }
+    }
+    ranges
+}
+
+/// Scans `db` for a face other than `base_face` that covers `c`, in face-iteration order.
+///
+/// When `strict` is set, a face is only considered if its style, weight and stretch all match
+/// `base_face` exactly; a mismatch on any one of them is enough to skip it. When unset, style,
+/// weight and stretch are all ignored, so this should only be used once a strict pass has failed.
+fn find_fallback_face(
+    db: &Database,
+    c: char,
+    base_face: &fontdb::FaceInfo,
+    used_fonts: &[ID],
+    strict: bool,
+) -> Option<ID> {
+    for face in db.faces() {
+        // Ignore fonts, that were used for shaping already.
+        if used_fonts.contains(&face.id) {
+            continue;
+        }
+
+        if strict
+            && (base_face.style != face.style
+                || base_face.weight != face.weight
+                || base_face.stretch != face.stretch)
+        {
+            continue;
+        }
+
+        if !face_has_char(db, face.id, c) {
+            continue;
+        }
+
+        let base_family = base_face
+            .families
+            .iter()
+            .find(|f| f.1 == fontdb::Language::English_UnitedStates)
+            .unwrap_or(&base_face.families[0]);
+
+        let new_family = face
+            .families
+            .iter()
+            .find(|f| f.1 == fontdb::Language::English_UnitedStates)
+            .unwrap_or(&base_face.families[0]);
+
+        log::warn!("Fallback from {} to {}.", base_family.0, new_family.0);
+        return Some(face.id);
+    }
+
+    None
+}
+
+/// Returns whether `id`'s face covers `c`, building and caching its coverage on first use.
+fn face_has_char(db: &Database, id: ID, c: char) -> bool {
+    let Some(face) = db.face(id) else {
+        return false;
+    };
+    let fingerprint = face_fingerprint(face);
+
+    let mut cache = coverage_cache().lock().unwrap();
+    if cache_entry_is_stale(&cache, &id, &fingerprint) {
+        cache.insert(
+            id,
+            CachedCoverage {
+                fingerprint,
+                ranges: build_coverage(db, id),
+            },
+        );
+    }
+
+    coverage_covers(&cache.get(&id).unwrap().ranges, c)
+}
+
+/// Whether `cache`'s entry for `id` is missing or was computed for a different face, i.e.
+/// whether `id` collided across two independently constructed `Database`s and needs recomputing.
+fn cache_entry_is_stale<K: Eq + std::hash::Hash>(
+    cache: &HashMap<K, CachedCoverage>,
+    id: &K,
+    fingerprint: &(String, u32),
+) -> bool {
+    cache
+        .get(id)
+        .is_none_or(|cached| &cached.fingerprint != fingerprint)
+}
+
+/// Binary-searches a sorted, disjoint list of inclusive ranges for `c`.
+fn coverage_covers(ranges: &[(u32, u32)], c: char) -> bool {
+    let c = c as u32;
+    ranges
+        .binary_search_by(|(start, end)| {
+            if c < *start {
+                std::cmp::Ordering::Greater
+            } else if c > *end {
+                std::cmp::Ordering::Less
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// One of the five CSS generic font families.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum GenericFamily {
+    Serif,
+    SansSerif,
+    Cursive,
+    Fantasy,
+    Monospace,
+}
+
+impl GenericFamily {
+    fn from_font_family(family: &FontFamily) -> Option<Self> {
+        match family {
+            FontFamily::Serif => Some(GenericFamily::Serif),
+            FontFamily::SansSerif => Some(GenericFamily::SansSerif),
+            FontFamily::Cursive => Some(GenericFamily::Cursive),
+            FontFamily::Fantasy => Some(GenericFamily::Fantasy),
+            FontFamily::Monospace => Some(GenericFamily::Monospace),
+            FontFamily::Named(_) => None,
+        }
+    }
+}
+
+/// The concrete family name a generic family resolves to on the current platform, computed once
+/// and memoized for the lifetime of the process (resolving it, e.g. via `fc-match`, is far too
+/// slow to redo on every `find_font` call).
+fn generic_family_cache() -> &'static Mutex<HashMap<GenericFamily, Option<String>>> {
+    static CACHE: OnceLock<Mutex<HashMap<GenericFamily, Option<String>>>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Resolves a generic family to the concrete family name the platform actually uses for it,
+/// e.g. `monospace` to `"DejaVu Sans Mono"` on a typical Linux desktop.
+fn resolve_generic_family(generic: GenericFamily) -> Option<String> {
+    let mut cache = generic_family_cache().lock().unwrap();
+    cache
+        .entry(generic)
+        .or_insert_with(|| platform_default_family(generic))
+        .clone()
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn platform_default_family(generic: GenericFamily) -> Option<String> {
+    // Mirrors what the old usvg `fontdb` did: ask Fontconfig, which already knows the user's
+    // and distribution's configured defaults for each generic keyword.
+    let keyword = match generic {
+        GenericFamily::Serif => "serif",
+        GenericFamily::SansSerif => "sans-serif",
+        GenericFamily::Cursive => "cursive",
+        GenericFamily::Fantasy => "fantasy",
+        GenericFamily::Monospace => "monospace",
+    };
+
+    let output = std::process::Command::new("fc-match")
+        .args(["--format=%{family}", keyword])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let name = String::from_utf8(output.stdout).ok()?;
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+#[cfg(target_os = "macos")]
+fn platform_default_family(generic: GenericFamily) -> Option<String> {
+    let name = match generic {
+        GenericFamily::Serif => "Times New Roman",
+        GenericFamily::SansSerif => "Helvetica",
+        GenericFamily::Cursive => "Apple Chancery",
+        GenericFamily::Fantasy => "Papyrus",
+        GenericFamily::Monospace => "Menlo",
+    };
+    Some(name.to_string())
+}
+
+#[cfg(target_os = "windows")]
+fn platform_default_family(generic: GenericFamily) -> Option<String> {
+    let name = match generic {
+        GenericFamily::Serif => "Times New Roman",
+        GenericFamily::SansSerif => "Arial",
+        GenericFamily::Cursive => "Comic Sans MS",
+        GenericFamily::Fantasy => "Gabriola",
+        GenericFamily::Monospace => "Courier New",
+    };
+    Some(name.to_string())
+}
+
+#[cfg(not(any(unix, target_os = "windows")))]
+fn platform_default_family(_generic: GenericFamily) -> Option<String> {
+    None
+}
+
 impl FontProvider for Database {
     fn find_font(&self, font: &Font) -> Option<ID> {
         let mut name_list = Vec::new();
+        let mut resolved_generics = Vec::new();
         for family in &font.families {
             name_list.push(match family {
                 FontFamily::Serif => fontdb::Family::Serif,
@@ -15,6 +292,19 @@ impl FontProvider for Database {
                 FontFamily::Monospace => fontdb::Family::Monospace,
                 FontFamily::Named(s) => fontdb::Family::Name(s),
             });
+
+            if let Some(generic) = GenericFamily::from_font_family(family) {
+                if let Some(name) = resolve_generic_family(generic) {
+                    resolved_generics.push(name);
+                }
+            }
+        }
+
+        // Splice the platform's concrete resolution of any generic families in right after the
+        // user's explicit families, so e.g. `monospace` actually picks a monospace face instead
+        // of falling all the way through to the serif fallback below.
+        for name in &resolved_generics {
+            name_list.push(fontdb::Family::Name(name));
         }
 
         // Use the default font as fallback.
@@ -61,55 +351,97 @@ impl FontProvider for Database {
     }
 
     fn find_fallback_font(&self, c: char, base_font_id: ID, used_fonts: &[ID]) -> Option<ID> {
-        // Iterate over fonts and check if any of them support the specified char.
-        for face in self.faces() {
-            // Ignore fonts, that were used for shaping already.
-            if used_fonts.contains(&face.id) {
-                continue;
-            }
+        let base_face = self.face(base_font_id)?;
 
-            // Check that the new face has the same style.
-            let base_face = self.face(base_font_id)?;
-            if base_face.style != face.style
-                && base_face.weight != face.weight
-                && base_face.stretch != face.stretch
-            {
-                continue;
-            }
+        // Prefer a face that matches style, weight and stretch exactly; only fall back to a
+        // looser match (ignoring all three) if nothing strict covers the character.
+        find_fallback_face(self, c, base_face, used_fonts, true)
+            .or_else(|| find_fallback_face(self, c, base_face, used_fonts, false))
+    }
 
-            let has_char = self
-                .with_face_data(face.id, |font_data, face_index| -> Option<bool> {
-                    let font = ttf_parser::Face::parse(font_data, face_index).ok()?;
-                    font.glyph_index(c)?;
-                    Some(true)
-                })
-                .flatten()
-                .unwrap_or(false);
-
-            if !has_char {
-                continue;
-            }
+    fn with_database(&self, f: &mut dyn FnMut(&Database)) {
+        f(self);
+    }
+}
 
-            let base_family = base_face
-                .families
-                .iter()
-                .find(|f| f.1 == fontdb::Language::English_UnitedStates)
-                .unwrap_or(&base_face.families[0]);
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
 
-            let new_family = face
-                .families
-                .iter()
-                .find(|f| f.1 == fontdb::Language::English_UnitedStates)
-                .unwrap_or(&base_face.families[0]);
+    use super::{cache_entry_is_stale, coverage_covers, merge_sorted_into_ranges, symbol_remap, CachedCoverage};
 
-            log::warn!("Fallback from {} to {}.", base_family.0, new_family.0);
-            return Some(face.id);
-        }
+    #[test]
+    fn merges_adjacent_and_separates_disjoint_codepoints() {
+        let ranges = merge_sorted_into_ranges(vec![10, 11, 12, 20, 5, 6, 12]);
+        assert_eq!(ranges, vec![(5, 6), (10, 12), (20, 20)]);
+    }
 
-        None
+    #[test]
+    fn merge_of_empty_input_is_empty() {
+        assert_eq!(merge_sorted_into_ranges(vec![]), vec![]);
     }
 
-    fn with_database(&self, f: &mut dyn FnMut(&Database)) {
-        f(self);
+    #[test]
+    fn covers_reports_chars_inside_and_outside_ranges() {
+        let ranges = vec![(0x41, 0x5A), (0x61, 0x7A)];
+        assert!(coverage_covers(&ranges, 'A'));
+        assert!(coverage_covers(&ranges, 'Z'));
+        assert!(coverage_covers(&ranges, 'm'));
+        assert!(!coverage_covers(&ranges, '0'));
+        assert!(!coverage_covers(&ranges, '{'));
+    }
+
+    #[test]
+    fn covers_is_false_for_empty_coverage() {
+        assert!(!coverage_covers(&[], 'A'));
+    }
+
+    #[test]
+    fn symbol_remap_maps_f000_range_back_to_the_real_codepoint() {
+        assert_eq!(symbol_remap(0xF041), Some(0x41));
+        assert_eq!(symbol_remap(0xF000), Some(0));
+        assert_eq!(symbol_remap(0xF0FF), Some(0xFF));
+    }
+
+    #[test]
+    fn symbol_remap_ignores_codepoints_outside_the_symbol_range() {
+        assert_eq!(symbol_remap(0x41), None);
+        assert_eq!(symbol_remap(0xEFFF), None);
+        assert_eq!(symbol_remap(0xF100), None);
+    }
+
+    #[test]
+    fn missing_cache_entry_is_stale() {
+        let cache: HashMap<u32, CachedCoverage> = HashMap::new();
+        assert!(cache_entry_is_stale(&cache, &1, &("Face".to_string(), 0)));
+    }
+
+    #[test]
+    fn cache_entry_with_matching_fingerprint_is_not_stale() {
+        let mut cache = HashMap::new();
+        cache.insert(
+            1,
+            CachedCoverage {
+                fingerprint: ("Face".to_string(), 0),
+                ranges: vec![(0x41, 0x5A)],
+            },
+        );
+        assert!(!cache_entry_is_stale(&cache, &1, &("Face".to_string(), 0)));
+    }
+
+    #[test]
+    fn cache_entry_from_a_colliding_id_with_a_different_fingerprint_is_stale() {
+        // Simulates two independently constructed `Database`s handing out the same `ID` to two
+        // different faces: the cached entry under that `ID` was computed for "Other Face", but
+        // we're now asking about "Face", so it must be treated as stale and rebuilt.
+        let mut cache = HashMap::new();
+        cache.insert(
+            1,
+            CachedCoverage {
+                fingerprint: ("Other Face".to_string(), 0),
+                ranges: vec![(0x41, 0x5A)],
+            },
+        );
+        assert!(cache_entry_is_stale(&cache, &1, &("Face".to_string(), 0)));
     }
 }
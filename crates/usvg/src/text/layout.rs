@@ -0,0 +1,196 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Shapes and positions a text node's spans, turning source text into glyph runs ready for
+//! [`crate::text::flatten::flatten`] to convert into outlines.
+
+use rustybuzz::ttf_parser;
+
+use crate::layout::{BBox, Font, Rect, ResolvedFont, Text};
+use crate::text::{
+    rustybuzz_features, tag_from_chars, FontProvider, FontProviderExt, FontVariantCaps,
+};
+
+/// A single, already-shaped and already-positioned glyph.
+#[derive(Clone, Copy, Debug)]
+pub struct PositionedGlyph {
+    pub glyph_id: ttf_parser::GlyphId,
+    pub font: ResolvedFont,
+    pub font_size: f32,
+    pub x: f32,
+    pub y: f32,
+    /// Set when this glyph belongs to a run whose `font-variant-caps` couldn't be satisfied by
+    /// the face's own `smcp`/`c2sc` GSUB feature, so it must be rendered at
+    /// [`crate::text::small_caps_scale`] instead of full size to fake the small-caps look.
+    pub synthetic_small_caps: bool,
+}
+
+/// A shaped, positioned run of glyphs belonging to one [`crate::TextSpan`].
+#[derive(Clone, Debug, Default)]
+pub struct TextFragment {
+    pub font: Option<Font>,
+    pub glyphs: Vec<PositionedGlyph>,
+}
+
+/// Placeholder font size until `font-size` resolution is wired through from the parent node.
+const PLACEHOLDER_FONT_SIZE: f32 = 16.0;
+
+/// Uppercases `text` char-by-char, returning the result alongside a sorted list of
+/// `(byte_offset, was_lowercase)` pairs recording, for each *produced* run of chars (a source
+/// character can uppercase to more than one, e.g. German `ß` to `SS`), whether it came from a
+/// lowercase source character. `byte_offset` is into the returned string, so it lines up with
+/// rustybuzz's glyph `cluster` values for callers that shape the returned string.
+fn uppercase_with_lower_case_map(text: &str) -> (String, Vec<(u32, bool)>) {
+    let mut out = String::with_capacity(text.len());
+    let mut map = Vec::new();
+    for c in text.chars() {
+        map.push((out.len() as u32, c.is_lowercase()));
+        out.extend(c.to_uppercase());
+    }
+    (out, map)
+}
+
+/// Looks up whether the source character that produced the glyph at `cluster` (a byte offset
+/// into the string `map` was built from) was lowercase.
+fn is_lower_at_cluster(map: &[(u32, bool)], cluster: u32) -> bool {
+    match map.binary_search_by_key(&cluster, |&(offset, _)| offset) {
+        Ok(i) => map[i].1,
+        Err(0) => false,
+        Err(i) => map[i - 1].1,
+    }
+}
+
+/// Shapes and positions every span in `text`, producing the glyph runs [`flatten`] turns into
+/// outlines plus `text`'s (pre-stroke) bounding box.
+///
+/// [`flatten`]: crate::text::flatten::flatten
+pub(crate) fn layout_text(
+    text: &Text,
+    font_provider: &dyn FontProvider,
+) -> Option<(Vec<TextFragment>, BBox)> {
+    let mut fragments = Vec::new();
+    let mut bbox = BBox::default();
+    let mut pen_x = 0.0f32;
+
+    for chunk in &text.chunks {
+        for span in &chunk.spans {
+            let font = &span.font;
+            let id = font_provider.find_font(font)?;
+            let resolved = font_provider.load_font(id, &font.variations)?;
+            let mut features = rustybuzz_features(&font.features);
+
+            // `font-variant-caps`: prefer the face's own `smcp`/`c2sc` GSUB feature; only
+            // synthesize (uppercase + shrink to `small_caps_scale`) when it's missing. For plain
+            // `SmallCaps`, only characters that were actually lowercase in the source should
+            // shrink - an already-uppercase "ABC" in "ABC abc" must stay full size. `AllSmallCaps`
+            // shrinks every letter regardless of its original case, so it needs no such tracking.
+            let uppercased;
+            let mut text_to_shape = span.text.as_str();
+            let mut lower_case_map: Vec<(u32, bool)> = Vec::new();
+            let mut synthesize_small_caps = false;
+            if let Some(tag) = font.variant_caps.feature_tag() {
+                if font_provider.has_opentype_feature(id, tag) {
+                    features.push(rustybuzz::Feature::new(tag, 1, ..));
+                    // `c2sc` only turns capitals into small caps; the lowercase letters of
+                    // `AllSmallCaps` also need `smcp`.
+                    if font.variant_caps == FontVariantCaps::AllSmallCaps {
+                        let smcp = tag_from_chars('s', 'm', 'c', 'p');
+                        features.push(rustybuzz::Feature::new(smcp, 1, ..));
+                    }
+                } else if font.variant_caps == FontVariantCaps::AllSmallCaps {
+                    uppercased = span.text.to_uppercase();
+                    text_to_shape = &uppercased;
+                    synthesize_small_caps = true;
+                } else {
+                    let (upper, case_map) = uppercase_with_lower_case_map(&span.text);
+                    uppercased = upper;
+                    text_to_shape = &uppercased;
+                    lower_case_map = case_map;
+                    synthesize_small_caps = true;
+                }
+            }
+
+            let mut buffer = rustybuzz::UnicodeBuffer::new();
+            buffer.push_str(text_to_shape);
+            buffer.guess_segment_properties();
+
+            let glyphs = font_provider.with_face_data(id, |data, face_index| -> Option<Vec<PositionedGlyph>> {
+                let face = rustybuzz::Face::from_slice(data, face_index)?;
+                let output = rustybuzz::shape(&face, &features, buffer);
+
+                let scale = PLACEHOLDER_FONT_SIZE / resolved.units_per_em.get() as f32;
+                let mut glyphs = Vec::with_capacity(output.glyph_infos().len());
+                for (info, pos) in output.glyph_infos().iter().zip(output.glyph_positions()) {
+                    // For `AllSmallCaps`, `lower_case_map` is left empty and every glyph shrinks;
+                    // for plain `SmallCaps`, only glyphs whose source character was lowercase do.
+                    let glyph_is_synthetic_small_caps = synthesize_small_caps
+                        && (lower_case_map.is_empty()
+                            || is_lower_at_cluster(&lower_case_map, info.cluster));
+                    let glyph = PositionedGlyph {
+                        glyph_id: ttf_parser::GlyphId(info.glyph_id as u16),
+                        font: resolved,
+                        font_size: PLACEHOLDER_FONT_SIZE,
+                        x: pen_x + pos.x_offset as f32 * scale,
+                        y: pos.y_offset as f32 * scale,
+                        synthetic_small_caps: glyph_is_synthetic_small_caps,
+                    };
+                    pen_x += pos.x_advance as f32 * scale;
+                    bbox.expand(Rect {
+                        x: glyph.x,
+                        y: glyph.y - PLACEHOLDER_FONT_SIZE,
+                        width: pos.x_advance as f32 * scale,
+                        height: PLACEHOLDER_FONT_SIZE,
+                    });
+                    glyphs.push(glyph);
+                }
+
+                Some(glyphs)
+            })?;
+
+            fragments.push(TextFragment {
+                font: Some(font.clone()),
+                glyphs: glyphs.unwrap_or_default(),
+            });
+        }
+    }
+
+    Some((fragments, bbox))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_lower_at_cluster, uppercase_with_lower_case_map};
+
+    #[test]
+    fn uppercase_map_flags_only_the_lowercase_source_chars() {
+        let (upper, map) = uppercase_with_lower_case_map("Ab c");
+        assert_eq!(upper, "AB C");
+        // 'A' -> "A" (not lowercase), 'b' -> "B" (lowercase), ' ' -> " " (not lowercase),
+        // 'c' -> "C" (lowercase), each one byte long in both the source and the output.
+        assert_eq!(map, vec![(0, false), (1, true), (2, false), (3, true)]);
+    }
+
+    #[test]
+    fn uppercase_map_handles_multi_char_expansions() {
+        // German 'ß' uppercases to the two-char "SS"; the byte offset recorded is where that
+        // expansion starts, and it's flagged lowercase since 'ß' itself is.
+        let (upper, map) = uppercase_with_lower_case_map("ß!");
+        assert_eq!(upper, "SS!");
+        assert_eq!(map, vec![(0, true), (2, false)]);
+    }
+
+    #[test]
+    fn cluster_lookup_finds_the_char_a_mid_expansion_byte_belongs_to() {
+        let map = vec![(0u32, true), (2, false)];
+        // Byte 1 falls inside "SS" (bytes 0-1), which belongs to the lowercase source char at 0.
+        assert!(is_lower_at_cluster(&map, 0));
+        assert!(is_lower_at_cluster(&map, 1));
+        assert!(!is_lower_at_cluster(&map, 2));
+    }
+
+    #[test]
+    fn cluster_lookup_on_empty_map_is_false() {
+        assert!(!is_lower_at_cluster(&[], 0));
+    }
+}
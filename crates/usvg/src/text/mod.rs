@@ -4,16 +4,116 @@
 
 use ::fontdb::{Database, ID};
 use rustybuzz::ttf_parser;
+use rustybuzz::ttf_parser::Tag;
 use std::num::NonZeroU16;
 
 use crate::layout::ResolvedFont;
-use crate::{Font, Text};
+use crate::{Font, FontStyle, Text};
 
 mod flatten;
 mod fontdb;
 /// Provides access to the layout of a text node.
 pub mod layout;
 
+/// Builds an OpenType tag (e.g. the `wght` axis tag) from four ASCII characters, matching how
+/// the tag is written out in CSS and in the spec itself.
+pub(crate) fn tag_from_chars(a: char, b: char, c: char, d: char) -> Tag {
+    Tag::from_bytes(&[a as u8, b as u8, c as u8, d as u8])
+}
+
+/// A single `font-feature-settings` entry, e.g. `"liga" 1` or `"ss01" on`.
+///
+/// `tag` is the 4-byte OpenType feature tag (see [`tag_from_chars`]) and `value` is the feature's
+/// integer argument; for boolean GSUB/GPOS features this is simply `0` (off) or `1` (on).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FeatureTagValue {
+    pub tag: Tag,
+    pub value: u32,
+}
+
+/// A single `font-variation-settings` entry, e.g. `"wght" 600`.
+///
+/// `tag` is the 4-byte OpenType variation axis tag and `value` is the requested coordinate along
+/// that axis, in the units the `fvar` table defines for it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VariationValue {
+    pub tag: Tag,
+    pub value: f32,
+}
+
+/// Converts parsed `font-feature-settings` entries into the representation `rustybuzz`'s shaping
+/// call expects, each one active for the whole buffer that is passed to it.
+pub(crate) fn rustybuzz_features(features: &[FeatureTagValue]) -> Vec<rustybuzz::Feature> {
+    features
+        .iter()
+        .map(|f| rustybuzz::Feature::new(f.tag, f.value, ..))
+        .collect()
+}
+
+/// Which aspects of a weight/style mismatch between a requested and a resolved face may be
+/// synthesized, mirroring the keywords of the CSS `font-synthesis` property (minus `small-caps`,
+/// which is handled separately).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FontSynthesis {
+    pub weight: bool,
+    pub style: bool,
+}
+
+impl Default for FontSynthesis {
+    fn default() -> Self {
+        // The CSS initial value is `weight style small-caps`.
+        FontSynthesis {
+            weight: true,
+            style: true,
+        }
+    }
+}
+
+/// What, if anything, must be synthesized on top of a resolved face to honor the requested
+/// weight and style.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct SyntheticStyle {
+    /// Outlines should be emboldened, e.g. via dilation or a double-stroke, during flattening.
+    pub bold: bool,
+    /// Outlines should be sheared by [`SYNTHETIC_OBLIQUE_SKEW`] during flattening.
+    pub oblique: bool,
+}
+
+/// The weight delta (in CSS weight units, i.e. out of 1000) at or above which a resolved face is
+/// considered "far enough" from a requested bold weight to warrant synthetic emboldening.
+const SYNTHETIC_BOLD_THRESHOLD: i32 = 300;
+
+/// The skew applied to synthesize an oblique style when no italic/oblique face is available,
+/// expressed as the x-offset per unit of y, i.e. `tan(14°)` (roughly what browsers use for
+/// `font-synthesis: style`).
+pub(crate) const SYNTHETIC_OBLIQUE_SKEW: f32 = 0.249_328_01;
+
+/// The `font-variant-caps` values we support. `Normal` shapes runs as written; the other two
+/// request the face's `smcp`/`c2sc` GSUB features, falling back to synthesized small caps (see
+/// [`small_caps_scale`]) when the face doesn't have them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FontVariantCaps {
+    #[default]
+    Normal,
+    /// Lowercase letters are rendered as small capitals; uppercase letters are untouched.
+    SmallCaps,
+    /// Both lowercase and uppercase letters are rendered as small capitals.
+    AllSmallCaps,
+}
+
+impl FontVariantCaps {
+    /// The GSUB feature whose presence in the face decides whether this variant is shaped with
+    /// the face's own small caps or synthesized: `smcp` for `SmallCaps`, `c2sc` for
+    /// `AllSmallCaps`, and `None` for `Normal`.
+    pub(crate) fn feature_tag(self) -> Option<Tag> {
+        match self {
+            FontVariantCaps::Normal => None,
+            FontVariantCaps::SmallCaps => Some(tag_from_chars('s', 'm', 'c', 'p')),
+            FontVariantCaps::AllSmallCaps => Some(tag_from_chars('c', '2', 's', 'c')),
+        }
+    }
+}
+
 /// Convert a text into its paths. This is done in two steps:
 /// 1. We convert the text into glyphs and position them according to the rules specified in the
 /// SVG specifiation. While doing so, we also calculate the text bbox (which is not based on the
@@ -46,7 +146,17 @@ pub(crate) trait FontProviderExt {
     where
         P: FnOnce(&[u8], u32) -> T;
 
-    fn load_font(&self, id: ID) -> Option<ResolvedFont>;
+    /// Loads the metrics of `id`, instancing it along `variations` first so that the returned
+    /// metrics (ascent, descent, x-height, ...) reflect the requested variable-font instance.
+    /// Axes not present in the face's `fvar` table are ignored, with a logged warning.
+    fn load_font(&self, id: ID, variations: &[VariationValue]) -> Option<ResolvedFont>;
+
+    /// Compares `font`'s requested weight/style against the weight/style `id` actually resolved
+    /// to and decides what, if anything, should be synthesized to make up the difference.
+    fn synthetic_style(&self, font: &Font, id: ID) -> SyntheticStyle;
+
+    /// Returns whether `id`'s face exposes a GSUB or GPOS feature tagged `tag`, e.g. `smcp`.
+    fn has_opentype_feature(&self, id: ID, tag: Tag) -> bool;
 }
 
 impl<F: FontProvider + ?Sized> FontProviderExt for F {
@@ -65,9 +175,23 @@ impl<F: FontProvider + ?Sized> FontProviderExt for F {
     }
 
     #[inline(never)]
-    fn load_font(&self, id: ID) -> Option<ResolvedFont> {
+    fn load_font(&self, id: ID, variations: &[VariationValue]) -> Option<ResolvedFont> {
         self.with_face_data(id, |data, face_index| -> Option<ResolvedFont> {
-            let font = ttf_parser::Face::parse(data, face_index).ok()?;
+            let mut font = ttf_parser::Face::parse(data, face_index).ok()?;
+
+            if !variations.is_empty() {
+                let axes = font.variation_axes();
+                for variation in variations {
+                    if axes.into_iter().any(|axis| axis.tag == variation.tag) {
+                        font.set_variation(variation.tag, variation.value);
+                    } else {
+                        log::warn!(
+                            "Font has no '{}' variation axis, ignoring.",
+                            variation.tag
+                        );
+                    }
+                }
+            }
 
             let units_per_em = NonZeroU16::new(font.units_per_em())?;
 
@@ -89,6 +213,17 @@ impl<F: FontProvider + ?Sized> FontProviderExt for F {
                 }
             };
 
+            let cap_height = font
+                .capital_height()
+                .and_then(|h| u16::try_from(h).ok())
+                .and_then(NonZeroU16::new)
+                // Not every face carries `OS/2.sCapHeight` (it needs table version >= 2); a cap
+                // height around 1.4x the x-height is a reasonable stand-in, same ratio browsers
+                // fall back to.
+                .unwrap_or_else(|| {
+                    NonZeroU16::new((x_height.get() as f32 * 1.4) as u16).unwrap_or(x_height)
+                });
+
             let line_through = font.strikeout_metrics();
             let line_through_position = match line_through {
                 Some(metrics) => metrics.position,
@@ -128,6 +263,7 @@ impl<F: FontProvider + ?Sized> FontProviderExt for F {
                 ascent,
                 descent,
                 x_height,
+                cap_height,
                 underline_position,
                 underline_thickness,
                 line_through_position,
@@ -136,4 +272,45 @@ impl<F: FontProvider + ?Sized> FontProviderExt for F {
             })
         })?
     }
+
+    fn synthetic_style(&self, font: &Font, id: ID) -> SyntheticStyle {
+        let mut synthetic = SyntheticStyle::default();
+
+        self.with_database(&mut |db| {
+            let Some(face) = db.face(id) else { return };
+
+            if font.synthesis.weight {
+                synthetic.bold = i32::from(font.weight) - i32::from(face.weight.0)
+                    >= SYNTHETIC_BOLD_THRESHOLD;
+            }
+
+            if font.synthesis.style {
+                let wants_slant = matches!(font.style, FontStyle::Italic | FontStyle::Oblique);
+                synthetic.oblique = wants_slant && face.style == ::fontdb::Style::Normal;
+            }
+        });
+
+        synthetic
+    }
+
+    fn has_opentype_feature(&self, id: ID, tag: Tag) -> bool {
+        self.with_face_data(id, |data, face_index| -> Option<bool> {
+            let font = ttf_parser::Face::parse(data, face_index).ok()?;
+            let has_feature = |table: Option<ttf_parser::opentype_layout::LayoutTable>| {
+                table.is_some_and(|t| t.features.find(tag).is_some())
+            };
+            Some(has_feature(font.tables().gsub) || has_feature(font.tables().gpos))
+        })
+        .flatten()
+        .unwrap_or(false)
+    }
+}
+
+/// The uniform scale factor that brings a face's cap-height glyphs down to its x-height, used to
+/// synthesize small caps when a face lacks the GSUB feature (`smcp` or `c2sc`, see
+/// [`FontVariantCaps::feature_tag`]) that the requested `font-variant-caps` value checks for.
+///
+/// Baseline alignment is preserved: only the scale changes, the glyph's origin does not move.
+pub(crate) fn small_caps_scale(font: &ResolvedFont) -> f32 {
+    font.x_height.get() as f32 / font.cap_height.get() as f32
 }